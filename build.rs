@@ -0,0 +1,249 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/// `(SQLSTATE code, generated variant name)` pairs for the well-known codes
+/// documented by Postgres. Kept as a plain list here, rather than parsed from
+/// a data file, so the crate has no extra build-time inputs to ship.
+const CODES: &[(&str, &str)] = &[
+    ("00000", "SuccessfulCompletion"),
+    ("01000", "Warning"),
+    ("0100C", "WarningDynamicResultSetsReturned"),
+    ("01008", "WarningImplicitZeroBitPadding"),
+    ("01003", "WarningNullValueEliminatedInSetFunction"),
+    ("01007", "WarningPrivilegeNotGranted"),
+    ("01006", "WarningPrivilegeNotRevoked"),
+    ("01004", "WarningStringDataRightTruncation"),
+    ("01P01", "WarningDeprecatedFeature"),
+    ("02000", "NoData"),
+    ("02001", "NoAdditionalDynamicResultSetsReturned"),
+    ("03000", "SqlStatementNotYetComplete"),
+    ("08000", "ConnectionException"),
+    ("08003", "ConnectionDoesNotExist"),
+    ("08006", "ConnectionFailure"),
+    ("08001", "SqlclientUnableToEstablishSqlconnection"),
+    ("08004", "SqlserverRejectedEstablishmentOfSqlconnection"),
+    ("08007", "TransactionResolutionUnknown"),
+    ("08P01", "ProtocolViolation"),
+    ("09000", "TriggeredActionException"),
+    ("0A000", "FeatureNotSupported"),
+    ("0B000", "InvalidTransactionInitiation"),
+    ("0F000", "LocatorException"),
+    ("0F001", "InvalidLocatorSpecification"),
+    ("0L000", "InvalidGrantor"),
+    ("0LP01", "InvalidGrantOperation"),
+    ("0P000", "InvalidRoleSpecification"),
+    ("0Z000", "DiagnosticsException"),
+    ("0Z002", "StackedDiagnosticsAccessedWithoutActiveHandler"),
+    ("20000", "CaseNotFound"),
+    ("21000", "CardinalityViolation"),
+    ("22000", "DataException"),
+    ("2202E", "ArraySubscriptError"),
+    ("22021", "CharacterNotInRepertoire"),
+    ("22008", "DatetimeFieldOverflow"),
+    ("22012", "DivisionByZero"),
+    ("22005", "ErrorInAssignment"),
+    ("2200B", "EscapeCharacterConflict"),
+    ("22022", "IndicatorOverflow"),
+    ("22015", "IntervalFieldOverflow"),
+    ("2201E", "InvalidArgumentForLogarithm"),
+    ("22014", "InvalidArgumentForNtileFunction"),
+    ("22016", "InvalidArgumentForNthValueFunction"),
+    ("22018", "InvalidCharacterValueForCast"),
+    ("22007", "InvalidDatetimeFormat"),
+    ("22019", "InvalidEscapeCharacter"),
+    ("2200D", "InvalidEscapeOctet"),
+    ("22025", "InvalidEscapeSequence"),
+    ("22P06", "NonstandardUseOfEscapeCharacter"),
+    ("22010", "InvalidIndicatorParameterValue"),
+    ("22023", "InvalidParameterValue"),
+    ("2201B", "InvalidRegularExpression"),
+    ("22009", "InvalidTimeZoneDisplacementValue"),
+    ("2200C", "InvalidUseOfEscapeCharacter"),
+    ("22004", "NullValueNotAllowed"),
+    ("22002", "NullValueNoIndicatorParameter"),
+    ("22003", "NumericValueOutOfRange"),
+    ("22026", "StringDataLengthMismatch"),
+    ("22001", "StringDataRightTruncation"),
+    ("22011", "SubstringError"),
+    ("22027", "TrimError"),
+    ("22024", "UnterminatedCString"),
+    ("2200F", "ZeroLengthCharacterString"),
+    ("22P01", "FloatingPointException"),
+    ("22P02", "InvalidTextRepresentation"),
+    ("22P03", "InvalidBinaryRepresentation"),
+    ("22P04", "BadCopyFileFormat"),
+    ("22P05", "UntranslatableCharacter"),
+    ("23000", "IntegrityConstraintViolation"),
+    ("23001", "RestrictViolation"),
+    ("23502", "NotNullViolation"),
+    ("23503", "ForeignKeyViolation"),
+    ("23505", "UniqueViolation"),
+    ("23514", "CheckViolation"),
+    ("23P01", "ExclusionViolation"),
+    ("24000", "InvalidCursorState"),
+    ("25000", "InvalidTransactionState"),
+    ("25001", "ActiveSqlTransaction"),
+    ("25002", "BranchTransactionAlreadyActive"),
+    ("25008", "HeldCursorRequiresSameIsolationLevel"),
+    ("25003", "InappropriateAccessModeForBranchTransaction"),
+    ("25004", "InappropriateIsolationLevelForBranchTransaction"),
+    ("25005", "NoActiveSqlTransactionForBranchTransaction"),
+    ("25006", "ReadOnlySqlTransaction"),
+    ("25007", "SchemaAndDataStatementMixingNotSupported"),
+    ("25P01", "NoActiveSqlTransaction"),
+    ("25P02", "InFailedSqlTransaction"),
+    ("25P03", "IdleInTransactionSessionTimeout"),
+    ("26000", "InvalidSqlStatementName"),
+    ("27000", "TriggeredDataChangeViolation"),
+    ("28000", "InvalidAuthorizationSpecification"),
+    ("28P01", "InvalidPassword"),
+    ("2B000", "DependentPrivilegeDescriptorsStillExist"),
+    ("2BP01", "DependentObjectsStillExist"),
+    ("2D000", "InvalidTransactionTermination"),
+    ("2F000", "SqlRoutineException"),
+    ("2F005", "FunctionExecutedNoReturnStatement"),
+    ("2F002", "ModifyingSqlDataNotPermitted"),
+    ("2F003", "ProhibitedSqlStatementAttempted"),
+    ("2F004", "ReadingSqlDataNotPermitted"),
+    ("34000", "InvalidCursorName"),
+    ("38000", "ExternalRoutineException"),
+    ("39000", "ExternalRoutineInvocationException"),
+    ("3B000", "SavepointException"),
+    ("3B001", "InvalidSavepointSpecification"),
+    ("3D000", "InvalidCatalogName"),
+    ("3F000", "InvalidSchemaName"),
+    ("40000", "TransactionRollback"),
+    ("40002", "TransactionIntegrityConstraintViolation"),
+    ("40001", "SerializationFailure"),
+    ("40003", "StatementCompletionUnknown"),
+    ("40P01", "DeadlockDetected"),
+    ("42000", "SyntaxErrorOrAccessRuleViolation"),
+    ("42601", "SyntaxError"),
+    ("42501", "InsufficientPrivilege"),
+    ("42846", "CannotCoerce"),
+    ("42803", "GroupingError"),
+    ("42P20", "WindowingError"),
+    ("42P19", "InvalidRecursion"),
+    ("42830", "InvalidForeignKey"),
+    ("42602", "InvalidName"),
+    ("42622", "NameTooLong"),
+    ("42939", "ReservedName"),
+    ("42804", "DatatypeMismatch"),
+    ("42P18", "IndeterminateDatatype"),
+    ("42809", "WrongObjectType"),
+    ("42703", "UndefinedColumn"),
+    ("42883", "UndefinedFunction"),
+    ("42P01", "UndefinedTable"),
+    ("42P02", "UndefinedParameter"),
+    ("42704", "UndefinedObject"),
+    ("42701", "DuplicateColumn"),
+    ("42P03", "DuplicateCursor"),
+    ("42P04", "DuplicateDatabase"),
+    ("42723", "DuplicateFunction"),
+    ("42P05", "DuplicatePreparedStatement"),
+    ("42P06", "DuplicateSchema"),
+    ("42P07", "DuplicateTable"),
+    ("42712", "DuplicateAlias"),
+    ("42710", "DuplicateObject"),
+    ("42702", "AmbiguousColumn"),
+    ("42725", "AmbiguousFunction"),
+    ("42P08", "AmbiguousParameter"),
+    ("42P09", "AmbiguousAlias"),
+    ("42P10", "InvalidColumnReference"),
+    ("42611", "InvalidColumnDefinition"),
+    ("44000", "WithCheckOptionViolation"),
+    ("53000", "InsufficientResources"),
+    ("53100", "DiskFull"),
+    ("53200", "OutOfMemory"),
+    ("53300", "TooManyConnections"),
+    ("53400", "ConfigurationLimitExceeded"),
+    ("54000", "ProgramLimitExceeded"),
+    ("54001", "StatementTooComplex"),
+    ("54011", "TooManyColumns"),
+    ("54023", "TooManyArguments"),
+    ("55000", "ObjectNotInPrerequisiteState"),
+    ("55006", "ObjectInUse"),
+    ("55P02", "CantChangeRuntimeParam"),
+    ("55P03", "LockNotAvailable"),
+    ("57000", "OperatorIntervention"),
+    ("57014", "QueryCanceled"),
+    ("57P01", "AdminShutdown"),
+    ("57P02", "CrashShutdown"),
+    ("57P03", "CannotConnectNow"),
+    ("57P04", "DatabaseDropped"),
+    ("57P05", "IdleSessionTimeout"),
+    ("58000", "SystemError"),
+    ("58030", "IoError"),
+    ("58P01", "UndefinedFile"),
+    ("58P02", "DuplicateFile"),
+    ("F0000", "ConfigFileError"),
+    ("F0001", "LockFileExists"),
+    ("P0000", "PlpgsqlError"),
+    ("P0001", "RaiseException"),
+    ("P0002", "NoDataFound"),
+    ("P0003", "TooManyRows"),
+    ("P0004", "AssertFailure"),
+    ("XX000", "InternalError"),
+    ("XX001", "DataCorrupted"),
+    ("XX002", "IndexCorrupted"),
+];
+
+fn main() {
+    let out_dir = env::var_os("OUT_DIR").expect("OUT_DIR not set");
+    let dest_path = Path::new(&out_dir).join("sqlstate.rs");
+
+    let mut enum_variants = String::new();
+    let mut code_match_arms = String::new();
+    let mut phf_entries = String::new();
+
+    for (code, variant) in CODES {
+        let _ = writeln!(enum_variants, "    {variant},");
+        let _ = writeln!(
+            code_match_arms,
+            "            SqlState::{variant} => \"{code}\","
+        );
+        let _ = writeln!(phf_entries, "    \"{code}\" => SqlState::{variant},");
+    }
+
+    let generated = format!(
+        r#"/// Well-known SQLSTATE error codes, generated from the list in `build.rs`.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum SqlState {{
+{enum_variants}    /// A SQLSTATE code this crate doesn't have a named variant for.
+    Other(String),
+}}
+
+impl SqlState {{
+    /// The 5-character SQLSTATE code for this variant.
+    ///
+    /// This borrows from `self` rather than returning `&'static str`: named
+    /// variants hand back a `'static` literal, but `SqlState::Other` has to
+    /// hand back the borrowed `String` it was constructed with.
+    pub fn code(&self) -> &str {{
+        match self {{
+{code_match_arms}            SqlState::Other(code) => code,
+        }}
+    }}
+
+    /// Look up the `SqlState` for a SQLSTATE code, falling back to
+    /// `SqlState::Other` for codes this crate doesn't name.
+    ///
+    /// `from_code(code).code() == code` for any code, named or not.
+    pub fn from_code(code: &str) -> SqlState {{
+        static CODES: phf::Map<&'static str, SqlState> = phf::phf_map! {{
+{phf_entries}        }};
+
+        CODES
+            .get(code)
+            .cloned()
+            .unwrap_or_else(|| SqlState::Other(code.to_owned()))
+    }}
+}}
+"#
+    );
+
+    fs::write(&dest_path, generated).expect("failed to write generated sqlstate.rs");
+    println!("cargo:rerun-if-changed=build.rs");
+}
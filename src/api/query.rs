@@ -1,23 +1,145 @@
 use std::fmt::Debug;
+use std::future::Future;
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use futures::sink::{Sink, SinkExt};
-use futures::stream;
+use futures::{Stream, StreamExt};
 
 use super::portal::Portal;
+use super::results::{
+    into_row_description, DescribeResponse, FieldFormat, FieldInfo, FormatIterator, Response, Tag,
+};
 use super::stmt::Statement;
 use super::{ClientInfo, DEFAULT_NAME};
 use crate::error::{PgWireError, PgWireResult};
-use crate::messages::data::{DataRow, RowDescription};
+use crate::messages::data::DataRow;
 use crate::messages::extendedquery::{
-    Bind, Close, Describe, Execute, Parse, Sync as PgSync, TARGET_TYPE_BYTE_PORTAL,
-    TARGET_TYPE_BYTE_STATEMENT,
+    Bind, Close, Describe, Execute, NoData, ParameterDescription, Parse, Sync as PgSync,
+    TARGET_TYPE_BYTE_PORTAL, TARGET_TYPE_BYTE_STATEMENT,
+};
+use crate::messages::response::{
+    EmptyQueryResponse, PortalSuspended, ReadyForQuery, READY_STATUS_IDLE,
 };
-use crate::messages::response::{CommandComplete, ErrorResponse, ReadyForQuery, READY_STATUS_IDLE};
 use crate::messages::simplequery::Query;
 use crate::messages::PgWireBackendMessage;
 
+/// Number of rows fed to the client sink between flushes while streaming a
+/// query's result set, so a large result doesn't sit fully buffered in
+/// memory/socket buffers before anything reaches the wire.
+const ROW_FLUSH_INTERVAL: usize = 4096;
+
+impl Portal {
+    /// Resolve this portal's client-requested result-column format codes
+    /// (carried on the `Bind` message that created it) against the number
+    /// of columns the query actually returns, using the same `FormatIterator`
+    /// rule used for parameter format codes.
+    ///
+    /// Handlers read this when building their `DataRowEncoder` so a single
+    /// schema can serve text or binary output depending on what `Bind`
+    /// asked for, and `on_execute` reads it to describe the portal with the
+    /// format the rows will actually be sent in.
+    pub fn resolve_result_column_formats(&self, num_columns: usize) -> PgWireResult<Vec<FieldFormat>> {
+        FormatIterator::new(
+            self.result_column_format_codes().len(),
+            self.result_column_format_codes(),
+            num_columns,
+        )
+        .map(|formats| formats.collect())
+    }
+}
+
+/// What to do once [`drain_portal_rows`] has sent everything it can for one
+/// `Execute`.
+enum ExecuteOutcome<S> {
+    /// The row stream is exhausted. `rows_sent_this_call` is how many rows
+    /// *this* `Execute` sent (including a carried-over `lookahead`, if any)
+    /// — what `CommandComplete`'s tag should report. Postgres's tag counts
+    /// only the current Execute's rows, not the portal's running total
+    /// across however many Executes it took to drain it.
+    Complete { rows_sent_this_call: usize },
+    /// `max_rows` was hit with at least one more row still queued up
+    /// (pulled ahead of time as `lookahead` so the next `Execute` doesn't
+    /// have to re-run the query to find that out). `stream` and
+    /// `rows_sent` should be stashed on the portal via
+    /// `Portal::set_pending_stream` and resumed on the next `Execute`.
+    Suspended {
+        stream: S,
+        rows_sent: usize,
+        lookahead: PgWireResult<DataRow>,
+    },
+}
+
+/// Send up to `max_rows` rows in total: a `lookahead` row carried over from a
+/// previous suspended `Execute` (if any) goes first and counts toward that
+/// cap, and the rest are pulled from `stream`. Each row is handed to
+/// `send_row` as it goes out so the caller can stream it straight to the
+/// client instead of buffering the batch.
+///
+/// This is the suspension/resume decision logic behind
+/// `ExtendedQueryHandler::on_execute`, split out so it can be unit tested
+/// against a plain `futures::stream::iter` without a real `ClientInfo`/sink.
+/// `rows_sent` is the cumulative count already sent for this portal across
+/// prior `Execute`s; it's only threaded through for a further suspension's
+/// resume bookkeeping; `CommandComplete`'s tag only ever reports the current
+/// call's own count (see `ExecuteOutcome::Complete`).
+async fn drain_portal_rows<S, F, Fut>(
+    mut stream: S,
+    max_rows: usize,
+    mut rows_sent: usize,
+    lookahead: Option<PgWireResult<DataRow>>,
+    mut send_row: F,
+) -> PgWireResult<ExecuteOutcome<S>>
+where
+    S: Stream<Item = PgWireResult<DataRow>> + Unpin,
+    F: FnMut(PgWireResult<DataRow>) -> Fut,
+    Fut: Future<Output = PgWireResult<()>>,
+{
+    let mut sent_this_call = 0usize;
+    if let Some(row) = lookahead {
+        send_row(row).await?;
+        sent_this_call += 1;
+        rows_sent += 1;
+    }
+
+    let mut exhausted = false;
+    // `max_rows == 0` means "no limit" per the wire protocol.
+    while max_rows == 0 || sent_this_call < max_rows {
+        match stream.next().await {
+            Some(row) => {
+                send_row(row).await?;
+                sent_this_call += 1;
+                rows_sent += 1;
+            }
+            None => {
+                exhausted = true;
+                break;
+            }
+        }
+    }
+
+    if !exhausted {
+        // The cap was hit, but that doesn't tell us whether the stream is
+        // also exhausted right at the boundary. Pull one more row so the
+        // *next* Execute (if any) doesn't suspend needlessly.
+        match stream.next().await {
+            None => exhausted = true,
+            Some(row) => {
+                return Ok(ExecuteOutcome::Suspended {
+                    stream,
+                    rows_sent,
+                    lookahead: row,
+                });
+            }
+        }
+    }
+
+    debug_assert!(exhausted);
+    Ok(ExecuteOutcome::Complete {
+        rows_sent_this_call: sent_this_call,
+    })
+}
+
 /// handler for processing simple query.
 #[async_trait]
 pub trait SimpleQueryHandler: Send + Sync {
@@ -31,67 +153,66 @@ pub trait SimpleQueryHandler: Send + Sync {
         client.set_state(super::PgWireConnectionState::QueryInProgress);
         let resp = self.do_query(client, query.query()).await?;
         match resp {
-            QueryResponse::Data(row_description, data_rows, status) => {
-                let msgs = vec![PgWireBackendMessage::RowDescription(row_description)]
-                    .into_iter()
-                    .chain(data_rows.into_iter().map(PgWireBackendMessage::DataRow))
-                    .chain(
-                        vec![
-                            PgWireBackendMessage::CommandComplete(status),
-                            PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(
-                                READY_STATUS_IDLE,
-                            )),
-                        ]
-                        .into_iter(),
-                    )
-                    .map(Ok);
-
-                let mut msg_stream = stream::iter(msgs);
-                client.send_all(&mut msg_stream).await?;
-            }
-            QueryResponse::Empty(status) => {
+            Response::EmptyQuery => {
                 client
-                    .feed(PgWireBackendMessage::CommandComplete(status))
+                    .feed(PgWireBackendMessage::EmptyQueryResponse(
+                        EmptyQueryResponse::new(),
+                    ))
                     .await?;
+            }
+            Response::Execution(tag) => {
                 client
-                    .feed(PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(
-                        READY_STATUS_IDLE,
-                    )))
+                    .feed(PgWireBackendMessage::CommandComplete(tag.into()))
                     .await?;
-                client.flush().await?;
             }
-            QueryResponse::Error(e) => {
-                client.feed(PgWireBackendMessage::ErrorResponse(e)).await?;
+            Response::Query(query_response) => {
+                let row_schema = query_response.row_schema();
                 client
-                    .feed(PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(
-                        READY_STATUS_IDLE,
+                    .feed(PgWireBackendMessage::RowDescription(into_row_description(
+                        &row_schema,
                     )))
                     .await?;
-                client.flush().await?;
+
+                let mut rows_sent = 0usize;
+                let mut data_rows = query_response.data_rows();
+                while let Some(row) = data_rows.next().await {
+                    client.feed(PgWireBackendMessage::DataRow(row?)).await?;
+                    rows_sent += 1;
+                    if rows_sent % ROW_FLUSH_INTERVAL == 0 {
+                        client.flush().await?;
+                    }
+                }
+
+                client
+                    .feed(PgWireBackendMessage::CommandComplete(
+                        Tag::new_for_query(rows_sent).into(),
+                    ))
+                    .await?;
+            }
+            Response::Error(e) => {
+                client
+                    .feed(PgWireBackendMessage::ErrorResponse((*e).into()))
+                    .await?;
             }
         }
 
+        client
+            .feed(PgWireBackendMessage::ReadyForQuery(ReadyForQuery::new(
+                READY_STATUS_IDLE,
+            )))
+            .await?;
+        client.flush().await?;
+
         client.set_state(super::PgWireConnectionState::ReadyForQuery);
         Ok(())
     }
 
     ///
-    async fn do_query<C>(&self, client: &C, query: &str) -> PgWireResult<QueryResponse>
+    async fn do_query<C>(&self, client: &C, query: &str) -> PgWireResult<Response<'static>>
     where
         C: ClientInfo + Unpin + Send + Sync;
 }
 
-/// Query response types:
-///
-/// * Data: the response contains data rows,
-/// * Empty: the response has no data, like update/delete/insert
-/// * Error: an error response
-pub enum QueryResponse {
-    Data(RowDescription, Vec<DataRow>, CommandComplete),
-    Empty(CommandComplete),
-    Error(ErrorResponse),
-}
-
 #[async_trait]
 pub trait ExtendedQueryHandler: Send + Sync {
     async fn on_parse<C>(&self, client: &mut C, message: &Parse) -> PgWireResult<()>
@@ -113,6 +234,16 @@ pub trait ExtendedQueryHandler: Send + Sync {
         C::Error: Debug,
         PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
+        // Validate the parameter format codes up front using the same
+        // resolution rule the wire protocol defines for result-column format
+        // codes: 0 means all-text, 1 means one code for every parameter,
+        // otherwise there must be exactly one code per parameter.
+        FormatIterator::new(
+            message.parameter_format_codes().len(),
+            message.parameter_format_codes(),
+            message.parameters().len(),
+        )?;
+
         let portal = Portal::try_new(message, client)?;
         let id = portal.name().clone();
         client.portal_store_mut().put(&id, Arc::new(portal));
@@ -120,6 +251,14 @@ pub trait ExtendedQueryHandler: Send + Sync {
         Ok(())
     }
 
+    /// Execute a portal, streaming rows to the client and honoring the
+    /// `Execute` message's `max_rows` limit.
+    ///
+    /// When `max_rows` is positive and the row stream has more left once
+    /// that many have been sent, the portal is suspended: the remaining
+    /// stream is stashed on the portal and `PortalSuspended` is sent instead
+    /// of `CommandComplete`. The next `Execute` for the same portal resumes
+    /// from there rather than calling `do_query` again.
     async fn on_execute<C>(&self, client: &mut C, message: &Execute) -> PgWireResult<()>
     where
         C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
@@ -127,65 +266,180 @@ pub trait ExtendedQueryHandler: Send + Sync {
         PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
         let portal_name = message.name().as_ref().map_or(DEFAULT_NAME, String::as_str);
+        // `max_rows == 0` means "no limit" per the wire protocol.
+        let max_rows = message.max_rows() as usize;
+
         let store = client.portal_store();
-        if let Some(portal) = store.get(portal_name) {
-            match self.do_query(client, portal.as_ref()).await? {
-                QueryResponse::Data(head, rows, tail) => {
-                    if portal.row_description_requested() {
-                        client
-                            .send(PgWireBackendMessage::RowDescription(head))
-                            .await?;
-                    }
+        let portal = store
+            .get(portal_name)
+            .ok_or_else(|| PgWireError::PortalNotFound(portal_name.to_owned()))?;
 
-                    if !rows.is_empty() {
+        let (data_rows, rows_sent, lookahead) = match portal.take_pending_stream() {
+            Some(resumed) => resumed,
+            None => match self.do_query(client, portal.as_ref()).await? {
+                Response::Query(query_response) => {
+                    if portal.row_description_requested() {
+                        let row_schema = query_response.row_schema();
+                        // Describe the portal using the format each column
+                        // will actually be sent in, i.e. the client's
+                        // Bind-requested result-column formats, not whatever
+                        // format the handler's schema happened to pick.
+                        let formats = portal.resolve_result_column_formats(row_schema.len())?;
+                        let described_fields: Vec<FieldInfo> = row_schema
+                            .iter()
+                            .zip(formats)
+                            .map(|(field, format)| {
+                                FieldInfo::new(
+                                    field.name().clone(),
+                                    *field.table_id(),
+                                    *field.column_id(),
+                                    field.datatype().clone(),
+                                    *field.type_size(),
+                                    *field.type_modifier(),
+                                    format,
+                                )
+                            })
+                            .collect();
                         client
-                            .send_all(&mut stream::iter(
-                                rows.into_iter()
-                                    .map(|r| Ok(PgWireBackendMessage::DataRow(r))),
-                            ))
+                            .send(PgWireBackendMessage::RowDescription(into_row_description(
+                                &described_fields,
+                            )))
                             .await?;
                     }
-
+                    (query_response.data_rows(), 0usize, None)
+                }
+                Response::EmptyQuery => {
                     client
-                        .send(PgWireBackendMessage::CommandComplete(tail))
+                        .send(PgWireBackendMessage::EmptyQueryResponse(
+                            EmptyQueryResponse::new(),
+                        ))
                         .await?;
+                    return Ok(());
                 }
-                QueryResponse::Empty(tail) => {
+                Response::Execution(tag) => {
                     client
-                        .send(PgWireBackendMessage::CommandComplete(tail))
+                        .send(PgWireBackendMessage::CommandComplete(tag.into()))
                         .await?;
+                    return Ok(());
                 }
-                QueryResponse::Error(err) => {
+                Response::Error(err) => {
                     client
-                        .send(PgWireBackendMessage::ErrorResponse(err))
+                        .send(PgWireBackendMessage::ErrorResponse((*err).into()))
                         .await?;
+                    return Ok(());
                 }
-            }
+            },
+        };
 
+        let outcome = drain_portal_rows(data_rows, max_rows, rows_sent, lookahead, |row| async {
+            client.send(PgWireBackendMessage::DataRow(row?)).await?;
             Ok(())
-        } else {
-            Err(PgWireError::PortalNotFound(portal_name.to_owned()))
+        })
+        .await?;
+
+        let mut portal = portal;
+        match outcome {
+            ExecuteOutcome::Suspended {
+                stream,
+                rows_sent,
+                lookahead,
+            } => {
+                Arc::make_mut(&mut portal).set_pending_stream(stream, rows_sent, Some(lookahead));
+                client.portal_store_mut().put(portal_name, portal);
+                client
+                    .send(PgWireBackendMessage::PortalSuspended(
+                        PortalSuspended::new(),
+                    ))
+                    .await?;
+            }
+            ExecuteOutcome::Complete { rows_sent_this_call } => {
+                Arc::make_mut(&mut portal).clear_pending_stream();
+                client.portal_store_mut().put(portal_name, portal);
+                client
+                    .send(PgWireBackendMessage::CommandComplete(
+                        Tag::new_for_query(rows_sent_this_call).into(),
+                    ))
+                    .await?;
+            }
         }
-        // TODO: clear/remove portal?
+
+        Ok(())
     }
 
+    /// Describe either a portal or, per `message.target_type()`, a prepared
+    /// statement.
+    ///
+    /// Describing a statement returns a `ParameterDescription` built from the
+    /// inferred (or, failing that, Parse-declared) parameter types, followed
+    /// by a `RowDescription` or `NoData`. This is what drivers rely on when
+    /// they prepare-and-describe before binding.
     async fn on_describe<C>(&self, client: &mut C, message: &Describe) -> PgWireResult<()>
     where
         C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
         C::Error: Debug,
         PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
     {
-        let portal_name = message.name().as_ref().map_or(DEFAULT_NAME, String::as_str);
-        if let Some(mut portal) = client.portal_store().get(portal_name) {
-            // TODO: check if make_mut works for this
-            Arc::make_mut(&mut portal).set_row_description_requested(true);
-            client.portal_store_mut().put(portal_name, portal);
-            Ok(())
-        } else {
-            Err(PgWireError::PortalNotFound(portal_name.to_owned()))
+        let name = message.name().as_ref().map_or(DEFAULT_NAME, String::as_str);
+        match message.target_type() {
+            TARGET_TYPE_BYTE_STATEMENT => {
+                let stmt = client
+                    .stmt_store()
+                    .get(name)
+                    .ok_or_else(|| PgWireError::StatementNotFound(name.to_owned()))?;
+
+                let describe_response = self.do_describe_statement(client, &stmt).await?;
+                let param_types = describe_response
+                    .parameters()
+                    .clone()
+                    .unwrap_or_else(|| stmt.parameter_types().clone());
+
+                client
+                    .send(PgWireBackendMessage::ParameterDescription(
+                        ParameterDescription::new(param_types.iter().map(|t| t.oid()).collect()),
+                    ))
+                    .await?;
+
+                if describe_response.fields().is_empty() {
+                    client
+                        .send(PgWireBackendMessage::NoData(NoData::new()))
+                        .await?;
+                } else {
+                    client
+                        .send(PgWireBackendMessage::RowDescription(into_row_description(
+                            describe_response.fields(),
+                        )))
+                        .await?;
+                }
+
+                Ok(())
+            }
+            TARGET_TYPE_BYTE_PORTAL => {
+                if let Some(mut portal) = client.portal_store().get(name) {
+                    // TODO: check if make_mut works for this
+                    Arc::make_mut(&mut portal).set_row_description_requested(true);
+                    client.portal_store_mut().put(name, portal);
+                    Ok(())
+                } else {
+                    Err(PgWireError::PortalNotFound(name.to_owned()))
+                }
+            }
+            _ => Err(PgWireError::PortalNotFound(name.to_owned())),
         }
     }
 
+    /// Describe a prepared statement, inferring parameter types and result
+    /// columns for the client. Implementations that can't infer parameter
+    /// types should return `None` for `DescribeResponse::parameters()`; the
+    /// caller falls back to the types declared on the original `Parse`
+    /// message.
+    async fn do_describe_statement<C>(
+        &self,
+        client: &C,
+        statement: &Statement,
+    ) -> PgWireResult<DescribeResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync;
+
     async fn on_sync<C>(&self, client: &mut C, _message: &PgSync) -> PgWireResult<()>
     where
         C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
@@ -215,9 +469,137 @@ pub trait ExtendedQueryHandler: Send + Sync {
         Ok(())
     }
 
-    async fn do_query<C>(&self, client: &mut C, portal: &Portal) -> PgWireResult<QueryResponse>
+    async fn do_query<C>(&self, client: &mut C, portal: &Portal) -> PgWireResult<Response<'static>>
     where
         C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
         C::Error: Debug,
         PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>;
 }
+
+#[cfg(test)]
+mod test {
+    use futures::stream;
+
+    use super::*;
+
+    fn row() -> PgWireResult<DataRow> {
+        Ok(DataRow::new(Vec::new()))
+    }
+
+    #[test]
+    fn test_drain_portal_rows_suspends_and_resumes_across_multiple_executes() {
+        futures::executor::block_on(async {
+            let mut sent = Vec::new();
+            let source = stream::iter((0..5).map(|_| row()));
+
+            // First Execute: max_rows=2, nothing carried over yet. Only 2 of
+            // the 5 rows go out; the 3rd is pulled ahead as the lookahead
+            // rather than sent.
+            let outcome = drain_portal_rows(source, 2, 0, None, |r| {
+                sent.push(r.unwrap());
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+            assert_eq!(sent.len(), 2);
+            let (stream, rows_sent, lookahead) = match outcome {
+                ExecuteOutcome::Suspended {
+                    stream,
+                    rows_sent,
+                    lookahead,
+                } => (stream, rows_sent, lookahead),
+                ExecuteOutcome::Complete { .. } => panic!("expected suspension"),
+            };
+            assert_eq!(rows_sent, 2);
+
+            // Second Execute: the carried lookahead counts toward *this*
+            // call's own max_rows cap too, so only 1 more row from the stream
+            // is sent (the next one becomes the new lookahead) before
+            // suspending again — a lookahead doesn't give a call extra budget.
+            let outcome = drain_portal_rows(stream, 2, rows_sent, Some(lookahead), |r| {
+                sent.push(r.unwrap());
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+            assert_eq!(sent.len(), 4);
+            let (stream, rows_sent, lookahead) = match outcome {
+                ExecuteOutcome::Suspended {
+                    stream,
+                    rows_sent,
+                    lookahead,
+                } => (stream, rows_sent, lookahead),
+                ExecuteOutcome::Complete { .. } => panic!("expected suspension"),
+            };
+            assert_eq!(rows_sent, 4);
+
+            // Third Execute: just the lookahead plus an now-exhausted
+            // stream, so this one completes. Its CommandComplete tag should
+            // report only the single row *this* Execute sent, not the
+            // portal's 5-row running total.
+            let outcome = drain_portal_rows(stream, 2, rows_sent, Some(lookahead), |r| {
+                sent.push(r.unwrap());
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+            assert_eq!(sent.len(), 5);
+            match outcome {
+                ExecuteOutcome::Complete {
+                    rows_sent_this_call,
+                } => assert_eq!(rows_sent_this_call, 1),
+                ExecuteOutcome::Suspended { .. } => panic!("expected completion"),
+            }
+        });
+    }
+
+    #[test]
+    fn test_drain_portal_rows_exact_multiple_does_not_suspend() {
+        futures::executor::block_on(async {
+            let mut sent = Vec::new();
+            let source = stream::iter((0..4).map(|_| row()));
+
+            let outcome = drain_portal_rows(source, 4, 0, None, |r| {
+                sent.push(r.unwrap());
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+
+            // The stream had exactly max_rows left, so it should complete
+            // rather than suspend on an empty remainder.
+            assert_eq!(sent.len(), 4);
+            match outcome {
+                ExecuteOutcome::Complete {
+                    rows_sent_this_call,
+                } => assert_eq!(rows_sent_this_call, 4),
+                ExecuteOutcome::Suspended { .. } => {
+                    panic!("stream exhausted exactly at max_rows should not suspend")
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn test_drain_portal_rows_unlimited_drains_fully() {
+        futures::executor::block_on(async {
+            let mut sent = Vec::new();
+            let source = stream::iter((0..10).map(|_| row()));
+
+            let outcome = drain_portal_rows(source, 0, 0, None, |r| {
+                sent.push(r.unwrap());
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(sent.len(), 10);
+            match outcome {
+                ExecuteOutcome::Complete {
+                    rows_sent_this_call,
+                } => assert_eq!(rows_sent_this_call, 10),
+                ExecuteOutcome::Suspended { .. } => panic!("max_rows == 0 must never suspend"),
+            }
+        });
+    }
+}
@@ -8,7 +8,7 @@ use futures::{
 use postgres_types::{IsNull, ToSql, Type};
 
 use crate::{
-    error::{ErrorInfo, PgWireResult},
+    error::{ErrorInfo, PgWireError, PgWireResult},
     messages::{
         data::{DataRow, FieldDescription, RowDescription, FORMAT_CODE_BINARY, FORMAT_CODE_TEXT},
         response::CommandComplete,
@@ -78,6 +78,67 @@ impl FieldFormat {
     }
 }
 
+/// Resolves the per-column `FieldFormat` carried by a `Bind` message's format
+/// code list.
+///
+/// The wire protocol allows the format code count to be `0` (every column is
+/// text), `1` (the single code applies to every column), or exactly
+/// `num_columns` (one code per column). Any other count is a protocol
+/// violation.
+#[derive(Debug, Clone)]
+pub struct FormatIterator<'a> {
+    codes: &'a [i16],
+    single: bool,
+    num_columns: usize,
+    index: usize,
+}
+
+impl<'a> FormatIterator<'a> {
+    /// Create a `FormatIterator` from the format code count and codes carried
+    /// on the wire, validated against the number of columns they apply to
+    /// (result columns or parameters).
+    pub fn new(
+        count: usize,
+        codes: &'a [i16],
+        num_columns: usize,
+    ) -> PgWireResult<FormatIterator<'a>> {
+        if count != 0 && count != 1 && count != num_columns {
+            return Err(PgWireError::InvalidFormatCount {
+                count,
+                num_columns,
+            });
+        }
+
+        Ok(FormatIterator {
+            codes,
+            single: count == 1,
+            num_columns,
+            index: 0,
+        })
+    }
+}
+
+impl Iterator for FormatIterator<'_> {
+    type Item = FieldFormat;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.num_columns {
+            return None;
+        }
+
+        let format = if self.codes.is_empty() {
+            FieldFormat::Text
+        } else if self.single {
+            FieldFormat::from(self.codes[0])
+        } else {
+            FieldFormat::from(self.codes[self.index])
+        };
+
+        self.index += 1;
+        Some(format)
+    }
+}
+
 #[derive(Debug, new, Eq, PartialEq, Clone, Getters)]
 #[getset(get = "pub")]
 pub struct FieldInfo {
@@ -140,6 +201,7 @@ pub struct DataRowEncoder {
     buffer: DataRow,
     field_buffer: BytesMut,
     schema: Arc<Vec<FieldInfo>>,
+    formats: Option<Vec<FieldFormat>>,
     col_index: usize,
 }
 
@@ -151,6 +213,25 @@ impl DataRowEncoder {
             buffer: DataRow::new(Vec::with_capacity(ncols)),
             field_buffer: BytesMut::with_capacity(8),
             schema: fields,
+            formats: None,
+            col_index: 0,
+        }
+    }
+
+    /// New `DataRowEncoder` from schemas of column and the per-column
+    /// `FieldFormat` resolved from the client's requested result-column
+    /// format codes (see `FormatIterator`).
+    ///
+    /// `encode_field` will honor `formats` instead of the format recorded on
+    /// each `FieldInfo`, so a single schema can serve text or binary output
+    /// depending on what the `Bind` message asked for.
+    pub fn new_with_format(fields: Arc<Vec<FieldInfo>>, formats: Vec<FieldFormat>) -> DataRowEncoder {
+        let ncols = fields.len();
+        Self {
+            buffer: DataRow::new(Vec::with_capacity(ncols)),
+            field_buffer: BytesMut::with_capacity(8),
+            schema: fields,
+            formats: Some(formats),
             col_index: 0,
         }
     }
@@ -194,7 +275,11 @@ impl DataRowEncoder {
         T: ToSql + ToSqlText + Sized,
     {
         let data_type = self.schema[self.col_index].datatype();
-        let format = self.schema[self.col_index].format();
+        let format = self
+            .formats
+            .as_ref()
+            .map(|formats| &formats[self.col_index])
+            .unwrap_or_else(|| self.schema[self.col_index].format());
 
         let is_null = if *format == FieldFormat::Text {
             value.to_sql_text(data_type, &mut self.field_buffer)?
@@ -294,4 +379,51 @@ mod test {
         assert_eq!(row.fields()[1].as_ref().unwrap().len(), 4);
         assert_eq!(row.fields()[2].as_ref().unwrap().len(), 26);
     }
+
+    #[test]
+    fn test_data_row_encoder_new_with_format() {
+        let schema = Arc::new(vec![FieldInfo::new(
+            "id".into(),
+            None,
+            None,
+            Type::INT4,
+            None,
+            None,
+            FieldFormat::Text,
+        )]);
+
+        let mut text_encoder = DataRowEncoder::new(schema.clone());
+        text_encoder.encode_field(&7i32).unwrap();
+        let text_row = text_encoder.finish().unwrap();
+
+        // Overriding with FieldFormat::Binary should encode via `to_sql`
+        // rather than `to_sql_text`, regardless of what the schema's own
+        // FieldFormat says.
+        let mut binary_encoder = DataRowEncoder::new_with_format(schema, vec![FieldFormat::Binary]);
+        binary_encoder.encode_field(&7i32).unwrap();
+        let binary_row = binary_encoder.finish().unwrap();
+
+        // Binary int4 is the 4-byte wire representation; text is the ASCII
+        // digit(s), so the two encodings have different lengths here.
+        assert_eq!(text_row.fields()[0].as_ref().unwrap().len(), 1);
+        assert_eq!(binary_row.fields()[0].as_ref().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_format_iterator() {
+        // count == 0: every column is text
+        let formats: Vec<_> = FormatIterator::new(0, &[], 3).unwrap().collect();
+        assert_eq!(formats, vec![FieldFormat::Text; 3]);
+
+        // count == 1: the single code applies to every column
+        let formats: Vec<_> = FormatIterator::new(1, &[1], 3).unwrap().collect();
+        assert_eq!(formats, vec![FieldFormat::Binary; 3]);
+
+        // count == num_columns: one code per column
+        let formats: Vec<_> = FormatIterator::new(2, &[0, 1], 2).unwrap().collect();
+        assert_eq!(formats, vec![FieldFormat::Text, FieldFormat::Binary]);
+
+        // any other count is a protocol violation
+        assert!(FormatIterator::new(2, &[0, 1], 3).is_err());
+    }
 }
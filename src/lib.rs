@@ -0,0 +1,12 @@
+#[macro_use]
+extern crate derive_new;
+#[macro_use]
+extern crate getset;
+
+pub mod api;
+pub mod error;
+pub mod messages;
+pub mod types;
+mod sqlstate;
+
+pub use sqlstate::SqlState;
@@ -0,0 +1,17 @@
+//! Typed SQLSTATE codes for Postgres error/notice responses.
+//!
+//! The `SqlState` enum itself is generated by `build.rs` from an embedded
+//! `(code, variant name)` list, so it stays a simple `match`/`phf` table; this
+//! module only wires it into [`ErrorInfo`].
+
+use crate::error::ErrorInfo;
+
+include!(concat!(env!("OUT_DIR"), "/sqlstate.rs"));
+
+impl ErrorInfo {
+    /// Construct an `ErrorInfo` from a typed `SqlState` instead of a bare
+    /// SQLSTATE string, so callers can't typo or mis-case a code.
+    pub fn new_with_sqlstate(severity: String, sqlstate: SqlState, message: String) -> ErrorInfo {
+        ErrorInfo::new(severity, sqlstate.code().to_owned(), message)
+    }
+}